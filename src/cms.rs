@@ -0,0 +1,121 @@
+//! A Count-Min Sketch over string keys, used to estimate `uv:op` frequencies.
+//!
+//! The sketch is a `d × w` matrix of counters queried with `d` hash functions;
+//! a point query takes the per-row minimum. The hash functions are pinned by a
+//! deterministic, shareable seed ([`with_seeds`](CountMinSketch::with_seeds)) so
+//! sketches built by separate ingestion shards hash identically and can be
+//! summed with [`merge`](CountMinSketch::merge). Inserts default to a plain
+//! increment; [`set_conservative`](CountMinSketch::set_conservative) selects
+//! conservative-update (minimal-increment), which never underestimates and
+//! reduces the overestimation plain Count-Min suffers when low-count keys
+//! collide.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Base seed used by [`CountMinSketch::new`] so independent sketches agree on
+/// their hash functions without the caller threading a seed through.
+const DEFAULT_SEED: u64 = 0x5eed_b10c;
+
+/// A Count-Min Sketch keyed on `&str`.
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    seed: u64,
+    counters: Vec<Vec<u64>>,
+    conservative: bool,
+}
+
+impl CountMinSketch {
+    /// Build a sketch sized for error rate `epsilon` at `confidence` percent,
+    /// using the default shared seed.
+    pub fn new(epsilon: f64, confidence: f64) -> CountMinSketch {
+        CountMinSketch::with_seeds(epsilon, confidence, DEFAULT_SEED)
+    }
+
+    /// Build a sketch whose hash functions are fixed by `seed`, so every shard
+    /// constructed with the same dimensions and `seed` hashes identically and
+    /// the counter matrices can be combined by elementwise summation.
+    pub fn with_seeds(epsilon: f64, confidence: f64, seed: u64) -> CountMinSketch {
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let delta = 1.0 - confidence / 100.0;
+        let depth = ((1.0 / delta).ln().ceil() as usize).max(1);
+        CountMinSketch {
+            width,
+            depth,
+            seed,
+            counters: vec![vec![0; width]; depth],
+            conservative: false,
+        }
+    }
+
+    /// Select plain increment (`false`, the default) or conservative-update
+    /// (`true`) for subsequent [`put`](CountMinSketch::put) calls.
+    pub fn set_conservative(&mut self, conservative: bool) {
+        self.conservative = conservative;
+    }
+
+    /// The counter column in row `row` that `key` maps to.
+    fn index(&self, row: usize, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.seed.hash(&mut hasher);
+        (row as u64).hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % self.width as u64) as usize
+    }
+
+    /// Record one occurrence of `key`.
+    ///
+    /// Plain mode bumps every row. Conservative mode reads the `d` counters
+    /// first, finds their minimum `m`, and increments only those already at `m`,
+    /// leaving higher counters untouched.
+    pub fn put(&mut self, key: &str) {
+        let cols: Vec<usize> = (0..self.depth).map(|row| self.index(row, key)).collect();
+
+        if self.conservative {
+            let m = cols
+                .iter()
+                .enumerate()
+                .map(|(row, &col)| self.counters[row][col])
+                .min()
+                .unwrap_or(0);
+            for (row, &col) in cols.iter().enumerate() {
+                if self.counters[row][col] == m {
+                    self.counters[row][col] = m + 1;
+                }
+            }
+        } else {
+            for (row, &col) in cols.iter().enumerate() {
+                self.counters[row][col] += 1;
+            }
+        }
+    }
+
+    /// Point estimate for `key`: the minimum across rows, or `None` if unseen.
+    pub fn get(&self, key: &str) -> Option<u64> {
+        let estimate = (0..self.depth)
+            .map(|row| self.counters[row][self.index(row, key)])
+            .min()
+            .unwrap_or(0);
+
+        if estimate == 0 {
+            None
+        } else {
+            Some(estimate)
+        }
+    }
+
+    /// Add `other`'s counters into this sketch elementwise. Both sketches must
+    /// share dimensions and seed, which holds for shards built from the same
+    /// [`with_seeds`](CountMinSketch::with_seeds) arguments.
+    pub fn merge(&mut self, other: &CountMinSketch) {
+        debug_assert_eq!(self.width, other.width);
+        debug_assert_eq!(self.depth, other.depth);
+        debug_assert_eq!(self.seed, other.seed);
+        for (row, other_row) in self.counters.iter_mut().zip(other.counters.iter()) {
+            for (counter, added) in row.iter_mut().zip(other_row.iter()) {
+                *counter += *added;
+            }
+        }
+    }
+}
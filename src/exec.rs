@@ -0,0 +1,135 @@
+//! Spawns a user-supplied command once per completed `uv` record, turning EPP
+//! into a composable stage that can stream predictions into scoring or
+//! database-insertion scripts without an intermediate file.
+//!
+//! The command template is substituted per record: `{}` expands to the full
+//! record line, `{uv}` to the key, and `{k}` to the k-value. The record is also
+//! written to the child's stdin. Invocations run through a bounded thread pool
+//! so a slow consumer cannot stall parsing.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use anyhow::anyhow;
+
+/// A single record to hand to the downstream command.
+struct Job {
+    record: String,
+    uv: String,
+    k: usize,
+}
+
+/// Bounded pool of workers each running the `--exec` template per record.
+pub struct Executor {
+    tx: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    failures: Arc<AtomicUsize>,
+}
+
+impl Executor {
+    /// Build an executor from a whitespace-split command template, running up to
+    /// `jobs` invocations concurrently.
+    pub fn new(template: &str, jobs: usize) -> Result<Executor, anyhow::Error> {
+        let tokens: Vec<String> = template.split_whitespace().map(str::to_owned).collect();
+        if tokens.is_empty() {
+            return Err(anyhow!("--exec given an empty command"));
+        }
+
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(std::sync::Mutex::new(rx));
+        let failures = Arc::new(AtomicUsize::new(0));
+
+        let mut workers = Vec::with_capacity(jobs.max(1));
+        for _ in 0..jobs.max(1) {
+            let rx = Arc::clone(&rx);
+            let tokens = tokens.clone();
+            let failures = Arc::clone(&failures);
+            workers.push(std::thread::spawn(move || loop {
+                // Pop one job; the lock is released before the command runs.
+                let job = {
+                    let guard = rx.lock().expect("exec queue poisoned");
+                    guard.recv()
+                };
+                let Ok(job) = job else { break };
+                if run_one(&tokens, &job).is_err() {
+                    failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+
+        Ok(Executor {
+            tx: Some(tx),
+            workers,
+            failures,
+        })
+    }
+
+    /// Queue a completed record for the downstream command.
+    pub fn submit(&self, record: &str, uv: &str, k: usize) {
+        if let Some(tx) = &self.tx {
+            // Send only fails once all workers are gone, which cannot happen
+            // before `finish`.
+            let _ = tx.send(Job {
+                record: record.to_owned(),
+                uv: uv.to_owned(),
+                k,
+            });
+        }
+    }
+
+    /// Drain the queue, join the workers, and fail if any invocation failed.
+    pub fn finish(mut self) -> Result<(), anyhow::Error> {
+        // Drop the sender so workers observe the channel closing.
+        self.tx.take();
+        for worker in self.workers.drain(..) {
+            worker.join().expect("exec worker panicked");
+        }
+        let failures = self.failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            return Err(anyhow!("{} --exec invocation(s) failed", failures));
+        }
+        Ok(())
+    }
+}
+
+/// Substitute the placeholders into the template, run the command, and feed the
+/// record on its stdin.
+fn run_one(tokens: &[String], job: &Job) -> Result<(), anyhow::Error> {
+    let expanded: Vec<String> = tokens.iter().map(|t| substitute(t, job)).collect();
+
+    let mut child = Command::new(&expanded[0])
+        .args(&expanded[1..])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that exits without reading stdin closes the pipe; its exit
+        // status, not the write, decides whether the invocation failed.
+        let written = stdin
+            .write_all(job.record.as_bytes())
+            .and_then(|_| stdin.write_all(b"\n"));
+        if let Err(err) = written {
+            if err.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(err.into());
+            }
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("command exited with {}", status));
+    }
+    Ok(())
+}
+
+/// Expand `{}`, `{uv}`, and `{k}` placeholders within a single token.
+fn substitute(token: &str, job: &Job) -> String {
+    token
+        .replace("{uv}", &job.uv)
+        .replace("{k}", &job.k.to_string())
+        .replace("{}", &job.record)
+}
@@ -1,34 +1,46 @@
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, LineWriter, Write};
-use std::process::Command;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
 
 use anyhow::anyhow;
 use clap::App;
 use itertools::Itertools;
 
-use bocs::{cms::CountMinSketch, parser::Parser};
+use bocs::parser::Parser;
+
+use crate::cms::CountMinSketch;
+
+mod cms;
+mod exec;
+mod sort;
+mod temp;
 
 static CONFIDENCE: f64 = 99.0;
 
-fn main() -> Result<(), anyhow::Error> {
-    // Initialize paths
-    let quads_path = &format!(
-        "{}/epp-quads-{}.txt",
-        std::env::temp_dir()
-            .to_str()
-            .ok_or_else(|| anyhow!("Invalid path"))?,
-        std::process::id()
-    );
-    let unique_quads_path = &format!(
-        "{}/epp-unique-quads-{}.txt",
-        std::env::temp_dir()
-            .to_str()
-            .ok_or_else(|| anyhow!("Invalid path"))?,
-        std::process::id()
-    );
+/// Fixed base seed so every ingestion shard hashes identically and the merged
+/// sketch is bit-identical to the sequential result.
+static CMS_SEED: u64 = 0x5eed_b10c;
 
+fn main() -> Result<(), anyhow::Error> {
     // Read from cli
-    let (k, exponent, out) = init_cli()?;
+    let (k, exponent, out, chunk_bytes, jobs, conservative, exec_cmd, tempdir, keep_temp) =
+        init_cli()?;
+
+    // All intermediate files live under `tempdir` and are removed on drop, so an
+    // early `?` return cannot leak them. `--keep-temp` suppresses the cleanup.
+    let mut quads = temp::TempFile::new(&tempdir, format!("epp-quads-{}.txt", std::process::id()));
+    let mut unique_quads = temp::TempFile::new(
+        &tempdir,
+        format!("epp-unique-quads-{}.txt", std::process::id()),
+    );
+    if keep_temp {
+        quads.keep();
+        unique_quads.keep();
+    }
+    let quads_path = quads.path();
+    let unique_quads_path = unique_quads.path();
 
     // Get stdin_handle
     let stdin = std::io::stdin();
@@ -36,7 +48,6 @@ fn main() -> Result<(), anyhow::Error> {
 
     // Configure CMS
     let e = 1.0 / u32::pow(10, exponent) as f64;
-    let mut cms = CountMinSketch::new(e, CONFIDENCE);
 
     // Create parser
     let mut parser = Parser::new();
@@ -51,6 +62,10 @@ fn main() -> Result<(), anyhow::Error> {
 
     let mut count = 0;
 
+    // Build the CMS while streaming: each parsed uv:op pair is fanned out to a
+    // worker as it is read, so the whole dataset is never buffered in memory.
+    let mut ingestor = Ingestor::new(e, jobs, conservative);
+
     // Parse info from BLANT
     while let Some(cms_info) = parser.parse_cms(&mut stdin_handle)? {
         count += 1;
@@ -58,11 +73,14 @@ fn main() -> Result<(), anyhow::Error> {
         buffer_file
             .write_all(format!("{} {} {}\n", cms_info.uv, cms_info.c, cms_info.op).as_bytes())?;
 
-        // Create uv:op pair and put it in CMS
-        let uvop = format!("{}:{}", cms_info.uv, cms_info.op);
-        cms.put(&uvop);
+        // Feed the uv:op pair straight into ingestion.
+        ingestor.push(format!("{}:{}", cms_info.uv, cms_info.op));
     }
 
+    // Close the channels, join the workers, and reduce the per-shard sketches
+    // into one by elementwise counter summation.
+    let cms = ingestor.finish();
+
     let range = (e * count as f64).floor() as u64;
 
     let mut log_file = OpenOptions::new()
@@ -76,14 +94,36 @@ fn main() -> Result<(), anyhow::Error> {
         count, k, e, range
     )?;
 
-    // Use /usr/bin/sort to sort the seen uv:op pairs and eliminate duplicates
-    Command::new("sort")
-        .args(&["-u", "-k", "1", "-o", unique_quads_path, quads_path])
-        .output()?;
+    // Record the retained temp files so they can be inspected against the stats.
+    if keep_temp {
+        writeln!(
+            log_file,
+            "Kept temp files: {} {}",
+            quads_path.display(),
+            unique_quads_path.display()
+        )?;
+    }
+
+    // Sort the seen uv:op pairs and eliminate duplicates entirely in-crate, so
+    // the tool runs cross-platform with no dependency on an external `sort`.
+    sort::sort_unique(quads_path, unique_quads_path, chunk_bytes, &tempdir)?;
 
     // Buffered reader to read in unique, seen, uv:op pairs to eliminate noise in the CMS
     let mut seen = BufReader::new(File::open(unique_quads_path)?);
 
+    // When --exec is given, pipe each completed record into the downstream
+    // command instead of printing it.
+    let executor = match &exec_cmd {
+        Some(cmd) => Some(exec::Executor::new(cmd, jobs)?),
+        None => None,
+    };
+
+    // Emit a completed record, either to the downstream command or to stdout.
+    let emit = |record: &str, uv: &str| match &executor {
+        Some(ex) => ex.submit(record, uv, k),
+        None => println!("{}", record),
+    };
+
     // Buffers
     let mut line = String::new();
     let mut output = String::new();
@@ -104,7 +144,7 @@ fn main() -> Result<(), anyhow::Error> {
         // If we see a new uv pair, dump output, move on
         if uv != cur_uv {
             if !cur_uv.is_empty() {
-                println!("{}", output);
+                emit(&output, &cur_uv);
             }
             cur_uv = uv.to_owned();
             output = format!("{} {}", uv, c);
@@ -118,22 +158,103 @@ fn main() -> Result<(), anyhow::Error> {
         // Clear buffer
         line.clear();
     }
-    println!("{}", output);
+    emit(&output, &cur_uv);
 
-    // Clean up temp files
-    std::fs::remove_file(unique_quads_path)?;
-    std::fs::remove_file(quads_path)?;
+    // Wait for all downstream commands and surface any failure.
+    if let Some(ex) = executor {
+        ex.finish()?;
+    }
 
+    // Temp files are cleaned up when `quads` and `unique_quads` drop (unless
+    // `--keep-temp` was given).
     Ok(())
 }
 
-fn init_cli() -> Result<(usize, u32, String), anyhow::Error> {
+/// Streams parsed `uv:op` pairs to `jobs` worker threads as they arrive, each
+/// building its own sketch with identical dimensions and seed, then reduces the
+/// shards into one by elementwise counter summation. Pairs are round-robined to
+/// the workers as they are pushed, so the full dataset is never collected.
+struct Ingestor {
+    senders: Vec<Sender<String>>,
+    handles: Vec<JoinHandle<CountMinSketch>>,
+    next: usize,
+}
+
+impl Ingestor {
+    fn new(e: f64, jobs: usize, conservative: bool) -> Ingestor {
+        // Conservative-update is order-dependent and is not preserved by the
+        // per-shard build + elementwise-sum reduction: summing conservatively
+        // updated matrices reintroduces the overestimation the flag removes, and
+        // would make output depend on the worker count. Funnel every record
+        // through a single sketch so the minimal-increment semantics hold and
+        // the result is deterministic.
+        let jobs = if conservative { 1 } else { jobs.max(1) };
+
+        let mut senders = Vec::with_capacity(jobs);
+        let mut handles = Vec::with_capacity(jobs);
+        for _ in 0..jobs {
+            let (tx, rx) = mpsc::channel::<String>();
+            senders.push(tx);
+            handles.push(std::thread::spawn(move || {
+                let mut cms = CountMinSketch::with_seeds(e, CONFIDENCE, CMS_SEED);
+                cms.set_conservative(conservative);
+                while let Ok(uvop) = rx.recv() {
+                    cms.put(&uvop);
+                }
+                cms
+            }));
+        }
+
+        Ingestor {
+            senders,
+            handles,
+            next: 0,
+        }
+    }
+
+    /// Hand one `uv:op` pair to the next worker in round-robin order.
+    fn push(&mut self, uvop: String) {
+        let worker = self.next % self.senders.len();
+        self.senders[worker]
+            .send(uvop)
+            .expect("ingestion worker hung up");
+        self.next += 1;
+    }
+
+    /// Close the channels, join the workers, and reduce their sketches into one.
+    fn finish(self) -> CountMinSketch {
+        drop(self.senders);
+
+        let mut sketches = self
+            .handles
+            .into_iter()
+            .map(|h| h.join().expect("ingestion worker panicked"));
+        let mut cms = sketches.next().expect("at least one ingestion worker");
+        for other in sketches {
+            cms.merge(&other);
+        }
+        cms
+    }
+}
+
+fn init_cli(
+) -> Result<(usize, u32, String, usize, usize, bool, Option<String>, PathBuf, bool), anyhow::Error> {
     let matches = App::new("EPP")
         .version("0.4")
         .author("Shane Murphy, Elliott Allison, Maaz Adeeb")
         .arg_from_usage("-k <NUMBER> 'Sets the k-value that was used in BLANT'")
         .arg_from_usage("-e <NUMBER> 'Sets the error_rate to 1^-<NUMBER>'")
         .args_from_usage("-o <DIR> 'Sets the output dir")
+        .arg_from_usage(
+            "--chunk-bytes [BYTES] 'In-memory buffer size per sort run before spilling to disk'",
+        )
+        .arg_from_usage("-j, --jobs [N] 'Number of ingestion worker threads'")
+        .arg_from_usage("--conservative 'Use conservative-update (minimal-increment) CMS inserts'")
+        .arg_from_usage(
+            "--exec [CMD] 'Run CMD per record instead of printing ({}, {uv}, {k} placeholders)'",
+        )
+        .arg_from_usage("-t, --tempdir [DIR] 'Directory for intermediate files'")
+        .arg_from_usage("--keep-temp 'Do not delete intermediate files; log their paths'")
         .get_matches();
 
     let k = matches
@@ -151,5 +272,38 @@ fn init_cli() -> Result<(usize, u32, String), anyhow::Error> {
         .expect("Must supply o value")
         .parse::<String>()?;
 
-    Ok((k, e, out))
+    let chunk_bytes = match matches.value_of("chunk-bytes") {
+        Some(v) => v.parse::<usize>()?,
+        None => sort::DEFAULT_CHUNK_BYTES,
+    };
+
+    let jobs = match matches.value_of("jobs") {
+        Some(v) => v.parse::<usize>()?,
+        None => std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+    };
+
+    let conservative = matches.is_present("conservative");
+
+    let exec_cmd = matches.value_of("exec").map(str::to_owned);
+
+    let tempdir = match matches.value_of("tempdir") {
+        Some(v) => PathBuf::from(v),
+        None => std::env::temp_dir(),
+    };
+
+    let keep_temp = matches.is_present("keep-temp");
+
+    Ok((
+        k,
+        e,
+        out,
+        chunk_bytes,
+        jobs,
+        conservative,
+        exec_cmd,
+        tempdir,
+        keep_temp,
+    ))
 }
@@ -0,0 +1,175 @@
+//! In-process external merge sort used to dedup the `epp-quads` temp file.
+//!
+//! Replaces shelling out to `/usr/bin/sort -u -k1`, which is unavailable on
+//! Windows and minimal containers. The file can exceed memory, so we sort in
+//! bounded chunks, flush each sorted run to its own temp file, and k-way merge
+//! the runs back out. `-k1` with no end field keys field 1 through end of line,
+//! so `-u` dedups on the whole line: every distinct `uv c op` record is kept,
+//! including multiple ops per `uv`.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::temp::TempFile;
+
+/// Default in-memory buffer size before a run is flushed to disk (256 MiB).
+pub const DEFAULT_CHUNK_BYTES: usize = 256 * 1024 * 1024;
+
+/// The `uv` key of a quads line: everything up to the first whitespace field.
+fn uv_key(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+/// Sort `input` and write the `-u -k1` result to `output`, deduping on the whole
+/// line (field 1 through end of line) so every distinct `uv c op` record is
+/// kept. Lines are buffered in memory until they reach `chunk_bytes`, then
+/// sorted and flushed into `tempdir` as one run.
+pub fn sort_unique(
+    input: &Path,
+    output: &Path,
+    chunk_bytes: usize,
+    tempdir: &Path,
+) -> Result<(), anyhow::Error> {
+    let reader = BufReader::new(File::open(input)?);
+
+    // The run files delete themselves on drop, so an error anywhere below cannot
+    // leak them.
+    let mut runs: Vec<TempFile> = Vec::new();
+    let mut buffer: Vec<String> = Vec::new();
+    let mut buffered_bytes = 0usize;
+    let mut run_index = 0usize;
+
+    for line in reader.lines() {
+        let line = line?;
+        buffered_bytes += line.len() + 1;
+        buffer.push(line);
+
+        if buffered_bytes >= chunk_bytes {
+            flush_run(&mut buffer, tempdir, run_index, &mut runs)?;
+            buffered_bytes = 0;
+            run_index += 1;
+        }
+    }
+
+    // Flush the trailing run, if any.
+    if !buffer.is_empty() {
+        flush_run(&mut buffer, tempdir, run_index, &mut runs)?;
+    }
+
+    merge_runs(&runs, output)
+}
+
+/// Sort the buffered chunk by whole line and flush it to its own temp file.
+fn flush_run(
+    buffer: &mut Vec<String>,
+    tempdir: &Path,
+    run_index: usize,
+    runs: &mut Vec<TempFile>,
+) -> Result<(), anyhow::Error> {
+    buffer.sort();
+
+    let run = TempFile::new(
+        tempdir,
+        format!("epp-run-{}-{}.txt", std::process::id(), run_index),
+    );
+    let mut writer = BufWriter::new(File::create(run.path())?);
+    for line in buffer.iter() {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    buffer.clear();
+    runs.push(run);
+    Ok(())
+}
+
+/// A line pulled from a run, ordered by the whole line then the originating run.
+struct Entry {
+    line: String,
+    run: usize,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.line, self.run) == (&other.line, other.run)
+    }
+}
+impl Eq for Entry {}
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (&self.line, self.run).cmp(&(&other.line, other.run))
+    }
+}
+
+/// K-way merge across the sorted runs, emitting each distinct line once.
+fn merge_runs(runs: &[TempFile], output: &Path) -> Result<(), anyhow::Error> {
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    // Empty input: still produce an (empty) output file.
+    if runs.is_empty() {
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let mut readers: Vec<BufReader<File>> = runs
+        .iter()
+        .map(|run| File::open(run.path()).map(BufReader::new))
+        .collect::<Result<_, _>>()?;
+
+    // Prime the heap with the first line of every run.
+    let mut heap: BinaryHeap<Reverse<Entry>> = BinaryHeap::new();
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(entry) = next_entry(reader, run)? {
+            heap.push(Reverse(entry));
+        }
+    }
+
+    let mut last_line: Option<String> = None;
+    while let Some(Reverse(entry)) = heap.pop() {
+        if last_line.as_deref() != Some(entry.line.as_str()) {
+            writer.write_all(entry.line.as_bytes())?;
+            writer.write_all(b"\n")?;
+            last_line = Some(entry.line.clone());
+        }
+
+        if let Some(next) = next_entry(&mut readers[entry.run], entry.run)? {
+            heap.push(Reverse(next));
+        }
+    }
+
+    writer.flush()?;
+
+    // The run files are removed when `runs` drops in `sort_unique`.
+    Ok(())
+}
+
+/// Read the next line from `reader`, wrapping it as an [`Entry`]. A line with
+/// no `uv` key (an empty line) is an error.
+fn next_entry(
+    reader: &mut BufReader<File>,
+    run: usize,
+) -> Result<Option<Entry>, anyhow::Error> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let trimmed = line.trim_end_matches('\n');
+    if uv_key(trimmed).is_empty() {
+        return Err(anyhow!("Missing uv key in run line"));
+    }
+    Ok(Some(Entry {
+        line: trimmed.to_owned(),
+        run,
+    }))
+}
@@ -0,0 +1,43 @@
+//! RAII handle for an intermediate file: it owns a path and removes the file on
+//! `Drop`, so cleanup happens even when an early `?` return would otherwise leak
+//! the temp file. Call [`TempFile::keep`] to suppress deletion, which backs the
+//! `--keep-temp` debug mode used to compare the CMS range/coverage numbers
+//! against the raw quads.
+
+use std::path::{Path, PathBuf};
+
+/// A temp file that deletes itself when dropped, unless [`keep`](TempFile::keep)
+/// has been called.
+pub struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFile {
+    /// Reserve a temp file named `name` inside `dir`. Only the path is held here;
+    /// the file is created by whoever opens it.
+    pub fn new(dir: impl AsRef<Path>, name: impl AsRef<Path>) -> TempFile {
+        TempFile {
+            path: dir.as_ref().join(name),
+            keep: false,
+        }
+    }
+
+    /// The path to open, hand to the sorter, or log.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Suppress deletion on drop (`--keep-temp`).
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}